@@ -0,0 +1,520 @@
+use base64::URL_SAFE_NO_PAD;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rocket::{
+    fairing::AdHoc,
+    get,
+    tokio::{sync::RwLock, time},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::Display,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::config::AcmeConfig;
+
+#[derive(Debug)]
+pub enum Error {
+    Transport(reqwest::Error),
+    Json(serde_json::Error),
+    Key(ring::error::KeyRejected),
+    Unspecified(ring::error::Unspecified),
+    MissingNonce,
+    MissingHeader(&'static str),
+    ChallengeNotFound,
+    OrderNotReady(String),
+    Rcgen(rcgen::RcgenError),
+    Io(std::io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "ACME request failed: {}", e),
+            Error::Json(e) => write!(f, "failed to (de)serialize ACME payload: {}", e),
+            Error::Key(e) => write!(f, "invalid ACME account key: {}", e),
+            Error::Unspecified(_) => write!(f, "ACME signing operation failed"),
+            Error::MissingNonce => write!(f, "ACME server response carried no Replay-Nonce header"),
+            Error::MissingHeader(name) => write!(f, "ACME server response missing {} header", name),
+            Error::ChallengeNotFound => write!(f, "no pending challenge for token"),
+            Error::OrderNotReady(status) => write!(f, "ACME order is not ready for finalization (status: {})", status),
+            Error::Rcgen(e) => write!(f, "failed to build certificate request: {}", e),
+            Error::Io(e) => write!(f, "failed to read or write certificate files: {}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<rcgen::RcgenError> for Error {
+    fn from(e: rcgen::RcgenError) -> Error {
+        Error::Rcgen(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Directory {
+    #[serde(rename = "newNonce")]
+    new_nonce: String,
+    #[serde(rename = "newAccount")]
+    new_account: String,
+    #[serde(rename = "newOrder")]
+    new_order: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NewAccountPayload<'a> {
+    #[serde(rename = "termsOfServiceAgreed")]
+    terms_of_service_agreed: bool,
+    contact: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct NewOrderPayload<'a> {
+    identifiers: &'a [Identifier],
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Identifier {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    #[serde(default)]
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FinalizePayload {
+    csr: String,
+}
+
+/// Serves the HTTP-01 key-authorizations the ACME server polls for while an
+/// order is pending, keyed by challenge token.
+#[derive(Clone)]
+pub struct ChallengeStore {
+    authorizations: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl ChallengeStore {
+    pub fn new() -> Self {
+        ChallengeStore {
+            authorizations: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    async fn set(&self, token: String, key_authorization: String) {
+        self.authorizations.write().await.insert(token, key_authorization);
+    }
+
+    async fn remove(&self, token: &str) {
+        self.authorizations.write().await.remove(token);
+    }
+
+    async fn get(&self, token: &str) -> Option<String> {
+        self.authorizations.read().await.get(token).cloned()
+    }
+}
+
+impl Default for ChallengeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[get("/.well-known/acme-challenge/<token>")]
+pub async fn http01_challenge(challenges: &rocket::State<ChallengeStore>, token: String) -> Option<String> {
+    challenges.get(&token).await
+}
+
+/// An ACME account's ES256 signing key, generated fresh on first use.
+pub struct AccountKey {
+    keypair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AccountKey {
+    pub fn generate() -> Result<Self, Error> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .map_err(Error::Unspecified)?;
+        let keypair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())
+            .map_err(Error::Key)?;
+        Ok(AccountKey { keypair, rng })
+    }
+
+    fn jwk(&self) -> serde_json::Value {
+        let public_key = self.keypair.public_key().as_ref();
+        // Uncompressed SEC1 point: 0x04 || x (32 bytes) || y (32 bytes).
+        let x = &public_key[1..33];
+        let y = &public_key[33..65];
+        serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": base64::encode_config(x, URL_SAFE_NO_PAD),
+            "y": base64::encode_config(y, URL_SAFE_NO_PAD),
+        })
+    }
+
+    fn sign(&self, signing_input: &[u8]) -> Result<Vec<u8>, Error> {
+        self.keypair
+            .sign(&self.rng, signing_input)
+            .map(|sig| sig.as_ref().to_vec())
+            .map_err(Error::Unspecified)
+    }
+}
+
+/// A minimal ACME v2 client implementing the subset of the protocol needed to
+/// provision and renew an HTTP-01 validated certificate for `server_url`.
+pub struct AcmeClient {
+    client: reqwest::Client,
+    config: AcmeConfig,
+    account_key: AccountKey,
+    account_url: Option<String>,
+}
+
+impl AcmeClient {
+    pub fn new(config: AcmeConfig) -> Result<Self, Error> {
+        Ok(AcmeClient {
+            client: reqwest::Client::new(),
+            config,
+            account_key: AccountKey::generate()?,
+            account_url: None,
+        })
+    }
+
+    async fn directory(&self) -> Result<Directory, Error> {
+        Ok(self.client.get(&self.config.directory_url).send().await?.json().await?)
+    }
+
+    async fn fresh_nonce(&self, directory: &Directory) -> Result<String, Error> {
+        let response = self.client.head(&directory.new_nonce).send().await?;
+        nonce_from_response(&response)
+    }
+
+    /// Every ACME POST is a JWS over the freshly obtained replay nonce, per
+    /// RFC 8555 section 6.2: `protected` carries either `jwk` (before we have
+    /// an account) or `kid`, plus `nonce` and `url`; `payload` is the request body.
+    async fn post(&self, url: &str, payload: &serde_json::Value, nonce: String) -> Result<reqwest::Response, Error> {
+        let mut protected = serde_json::json!({
+            "alg": "ES256",
+            "nonce": nonce,
+            "url": url,
+        });
+        match &self.account_url {
+            Some(kid) => protected["kid"] = serde_json::Value::String(kid.clone()),
+            None => protected["jwk"] = self.account_key.jwk(),
+        }
+
+        let protected = base64::encode_config(serde_json::to_vec(&protected)?, URL_SAFE_NO_PAD);
+        let payload = if payload.is_null() {
+            String::new()
+        } else {
+            base64::encode_config(serde_json::to_vec(payload)?, URL_SAFE_NO_PAD)
+        };
+
+        let signing_input = format!("{}.{}", protected, payload);
+        let signature = self.account_key.sign(signing_input.as_bytes())?;
+        let signature = base64::encode_config(signature, URL_SAFE_NO_PAD);
+
+        let body = serde_json::json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": signature,
+        });
+
+        Ok(self
+            .client
+            .post(url)
+            .header("Content-Type", "application/jose+json")
+            .json(&body)
+            .send()
+            .await?)
+    }
+
+    /// Creates (or, per RFC 8555, looks up) the ACME account for our account key.
+    pub async fn ensure_account(&mut self) -> Result<(), Error> {
+        let directory = self.directory().await?;
+        let nonce = self.fresh_nonce(&directory).await?;
+
+        let contact = format!("mailto:{}", self.config.contact_email);
+        let payload = serde_json::to_value(NewAccountPayload {
+            terms_of_service_agreed: true,
+            contact: std::slice::from_ref(&contact),
+        })?;
+
+        let response = self.post(&directory.new_account, &payload, nonce).await?;
+        let account_url = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or(Error::MissingHeader("Location"))?
+            .to_string();
+
+        self.account_url = Some(account_url);
+        Ok(())
+    }
+
+    /// Runs the full order flow for `host`: submit order, complete the
+    /// HTTP-01 challenge via `challenges`, poll to `valid`, finalize with a
+    /// freshly generated key and CSR, and return the PEM certificate chain
+    /// together with the PEM-encoded private key it was issued for.
+    pub async fn obtain_certificate(&mut self, host: &str, challenges: &ChallengeStore) -> Result<(String, String), Error> {
+        if self.account_url.is_none() {
+            self.ensure_account().await?;
+        }
+
+        let directory = self.directory().await?;
+        let nonce = self.fresh_nonce(&directory).await?;
+
+        let identifiers = [Identifier {
+            kind: "dns".to_string(),
+            value: host.to_string(),
+        }];
+        let payload = serde_json::to_value(NewOrderPayload {
+            identifiers: &identifiers,
+        })?;
+        let response = self.post(&directory.new_order, &payload, nonce).await?;
+        let order_url = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or(Error::MissingHeader("Location"))?
+            .to_string();
+        let order: Order = response.json().await?;
+
+        for authz_url in &order.authorizations {
+            self.complete_http01(authz_url, challenges).await?;
+        }
+
+        let order = self.poll_order(&order_url, "ready").await?;
+
+        let mut cert_params = rcgen::CertificateParams::new(vec![host.to_string()]);
+        cert_params.distinguished_name = rcgen::DistinguishedName::new();
+        let cert_key = rcgen::Certificate::from_params(cert_params)?;
+        let csr_der = cert_key.serialize_request_der()?;
+
+        let nonce = self.fresh_nonce(&directory).await?;
+        let payload = serde_json::to_value(FinalizePayload {
+            csr: base64::encode_config(csr_der, URL_SAFE_NO_PAD),
+        })?;
+        self.post(&order.finalize, &payload, nonce).await?;
+
+        let order = self.poll_order(&order_url, "valid").await?;
+        let certificate_url = order.certificate.ok_or_else(|| Error::OrderNotReady("valid".to_string()))?;
+
+        let nonce = self.fresh_nonce(&directory).await?;
+        let response = self.post(&certificate_url, &serde_json::Value::Null, nonce).await?;
+        let certificate_pem = response.text().await?;
+
+        Ok((certificate_pem, cert_key.serialize_private_key_pem()))
+    }
+
+    async fn complete_http01(&mut self, authz_url: &str, challenges: &ChallengeStore) -> Result<(), Error> {
+        let directory = self.directory().await?;
+        let nonce = self.fresh_nonce(&directory).await?;
+        let response = self.post(authz_url, &serde_json::Value::Null, nonce).await?;
+        let authorization: Authorization = response.json().await?;
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|c| c.kind == "http-01")
+            .ok_or(Error::ChallengeNotFound)?
+            .clone();
+
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint()?);
+        challenges.set(challenge.token.clone(), key_authorization).await;
+
+        let nonce = self.fresh_nonce(&directory).await?;
+        self.post(&challenge.url, &serde_json::json!({}), nonce).await?;
+
+        // The server polls us back at /.well-known/acme-challenge/<token>; once
+        // it reports the challenge (and therefore the authorization) valid we
+        // no longer need to serve the key authorization.
+        self.poll_authorization(authz_url).await?;
+        challenges.remove(&challenge.token).await;
+
+        Ok(())
+    }
+
+    fn thumbprint(&self) -> Result<String, Error> {
+        // RFC 7638 JWK thumbprint over the canonical {crv,kty,x,y} member order.
+        let jwk = self.account_key.jwk();
+        let canonical = serde_json::json!({
+            "crv": jwk["crv"],
+            "kty": jwk["kty"],
+            "x": jwk["x"],
+            "y": jwk["y"],
+        });
+        let digest = ring::digest::digest(&ring::digest::SHA256, serde_json::to_vec(&canonical)?.as_slice());
+        Ok(base64::encode_config(digest.as_ref(), URL_SAFE_NO_PAD))
+    }
+
+    async fn poll_authorization(&self, authz_url: &str) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct AuthzStatus {
+            status: String,
+        }
+
+        for _ in 0..10 {
+            let directory = self.directory().await?;
+            let nonce = self.fresh_nonce(&directory).await?;
+            let response = self.post(authz_url, &serde_json::Value::Null, nonce).await?;
+            let status: AuthzStatus = response.json().await?;
+            if status.status == "valid" {
+                return Ok(());
+            }
+            time::sleep(Duration::from_secs(2)).await;
+        }
+
+        Err(Error::OrderNotReady("authorization".to_string()))
+    }
+
+    async fn poll_order(&self, order_url: &str, expected_status: &str) -> Result<Order, Error> {
+        for _ in 0..10 {
+            let directory = self.directory().await?;
+            let nonce = self.fresh_nonce(&directory).await?;
+            let response = self.post(order_url, &serde_json::Value::Null, nonce).await?;
+            let order: Order = response.json().await?;
+            if order.status == expected_status || order.status == "valid" {
+                return Ok(order);
+            }
+            time::sleep(Duration::from_secs(2)).await;
+        }
+
+        Err(Error::OrderNotReady(expected_status.to_string()))
+    }
+}
+
+fn nonce_from_response(response: &reqwest::Response) -> Result<String, Error> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+        .ok_or(Error::MissingNonce)
+}
+
+/// Ensures `config.cert_path`/`config.key_path` exist before Rocket's TLS
+/// listener binds to them, generating a self-signed certificate (and key)
+/// for `config.host` if either file is missing. On a genuinely fresh
+/// deployment there is no real certificate to bind to — ACME validation
+/// itself needs the server up to serve the HTTP-01 challenge — so this
+/// bootstrap cert lets Rocket start TLS immediately; `provisioning_fairing`
+/// then obtains and writes the real one shortly after liftoff (taking effect
+/// on the next restart, same as a renewal).
+pub fn ensure_bootstrap_certificate(config: &AcmeConfig) -> Result<(), Error> {
+    let cert_path = std::path::Path::new(&config.cert_path);
+    let key_path = std::path::Path::new(&config.key_path);
+    if cert_path.exists() && key_path.exists() {
+        return Ok(());
+    }
+
+    let cert = rcgen::generate_simple_self_signed(vec![config.host.clone()])?;
+    std::fs::write(cert_path, cert.serialize_pem()?)?;
+    std::fs::write(key_path, cert.serialize_private_key_pem())?;
+    println!(
+        "Generated a temporary self-signed certificate for {} pending ACME provisioning",
+        config.host
+    );
+
+    Ok(())
+}
+
+/// Fairing that obtains a certificate for `config.host` on startup, writes it
+/// to `config.cert_path`/`config.key_path`, and obtains a fresh one in the
+/// background every `renew_interval` (certificates from a Let's Encrypt-compatible CA are
+/// short-lived, so we renew well ahead of time rather than parsing `notAfter`).
+pub fn provisioning_fairing(config: AcmeConfig, renew_interval: Duration) -> AdHoc {
+    AdHoc::on_liftoff("ACME certificate provisioning", move |rocket| {
+        Box::pin(async move {
+            let challenges = match rocket.state::<ChallengeStore>() {
+                Some(store) => store.clone(),
+                None => {
+                    println!("ACME provisioning not started: no ChallengeStore in managed state");
+                    return;
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                let mut client = match AcmeClient::new(config.clone()) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        println!("Failed to initialize ACME account key: {}", e);
+                        return;
+                    }
+                };
+
+                loop {
+                    match client.obtain_certificate(&config.host, &challenges).await {
+                        Ok((cert_pem, key_pem)) => {
+                            if let Err(e) = std::fs::write(&config.cert_path, cert_pem) {
+                                println!("Failed to write ACME certificate: {}", e);
+                            }
+                            if let Err(e) = std::fs::write(&config.key_path, key_pem) {
+                                println!("Failed to write ACME private key: {}", e);
+                            }
+                            println!("Provisioned ACME certificate for {}", config.host);
+                        }
+                        Err(e) => println!("ACME certificate provisioning failed: {}", e),
+                    }
+
+                    time::sleep(renew_interval).await;
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,98 @@
+use crate::config::AssuranceLevel;
+use rocket::tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How long an issued assurance token remains redeemable. Generous enough to
+/// survive the redirect round-trip to `user_oob`/`user_inline`, short enough
+/// that a leaked token is useless shortly after.
+const TOKEN_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+struct AssuranceRecord {
+    attributes: String,
+    continuation: String,
+    attr_url: Option<String>,
+    level: AssuranceLevel,
+    issued_at: Instant,
+}
+
+/// Tracks PIN-confirmed assurance levels by opaque token, bound to the exact
+/// `attributes`/`continuation`/`attr_url` tuple they were issued for.
+#[derive(Clone)]
+pub struct AssuranceStore {
+    issued: Arc<RwLock<HashMap<String, AssuranceRecord>>>,
+}
+
+impl AssuranceStore {
+    pub fn new() -> Self {
+        AssuranceStore {
+            issued: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Issues a fresh single-use token recording that `level` assurance was
+    /// completed for the given tuple.
+    pub async fn issue(
+        &self,
+        attributes: String,
+        continuation: String,
+        attr_url: Option<String>,
+        level: AssuranceLevel,
+    ) -> String {
+        let token = Uuid::new_v4().to_string();
+        self.issued.write().await.insert(
+            token.clone(),
+            AssuranceRecord {
+                attributes,
+                continuation,
+                attr_url,
+                level,
+                issued_at: Instant::now(),
+            },
+        );
+        token
+    }
+
+    /// Consumes `token`, returning the assurance level it confirms only if it
+    /// exists, has not expired, and was issued for this exact tuple.
+    /// Anything else (missing, expired, mismatched, or no token at all) is
+    /// treated as no confirmation having happened, i.e. `AssuranceLevel::Low`.
+    pub async fn consume(
+        &self,
+        token: Option<&str>,
+        attributes: &str,
+        continuation: &str,
+        attr_url: Option<&str>,
+    ) -> AssuranceLevel {
+        let token = match token {
+            Some(token) => token,
+            None => return AssuranceLevel::default(),
+        };
+
+        let record = match self.issued.write().await.remove(token) {
+            Some(record) => record,
+            None => return AssuranceLevel::default(),
+        };
+
+        if record.issued_at.elapsed() > TOKEN_TTL
+            || record.attributes != attributes
+            || record.continuation != continuation
+            || record.attr_url.as_deref() != attr_url
+        {
+            return AssuranceLevel::default();
+        }
+
+        record.level
+    }
+}
+
+impl Default for AssuranceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
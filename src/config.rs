@@ -1,14 +1,126 @@
-use std::{collections::HashMap, convert::TryFrom, error::Error as StdError, fmt::Display};
+use std::{collections::{HashMap, HashSet}, convert::TryFrom, error::Error as StdError, fmt::Display, time::Duration};
 use serde::Deserialize;
 use id_contact_jwe::{SignKeyConfig, EncryptionKeyConfig};
+use id_contact_proto::AuthStatus;
 use josekit::{
     jwe::{JweEncrypter},
     jws::{JwsSigner},
 };
+use subtle::ConstantTimeEq;
+
+/// The strength of confirmation an attribute's release requires, following
+/// the eIDAS-style assurance tiers used elsewhere in the ID-Contact stack.
+/// Declaration order matters: derived `Ord` ranks `High` above `Substantial`
+/// above `Low`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AssuranceLevel {
+    Low,
+    Substantial,
+    High,
+}
+
+impl Default for AssuranceLevel {
+    fn default() -> Self {
+        AssuranceLevel::Low
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct AttributeConfig {
+    value: String,
+    #[serde(default)]
+    assurance: AssuranceLevel,
+}
+
+/// Forces the outcome that `user_oob`/`user_inline` assert, so integrators
+/// can exercise their negative paths without hand-crafting JWEs.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeOverride {
+    Success,
+    Failed,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".to_string()]
+}
+
+const LETS_ENCRYPT_STAGING_DIRECTORY: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+const LETS_ENCRYPT_PRODUCTION_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+fn default_acme_cert_path() -> String {
+    "acme_cert.pem".to_string()
+}
+
+fn default_acme_key_path() -> String {
+    "acme_key.pem".to_string()
+}
+
+fn default_acme_renew_interval_seconds() -> u64 {
+    60 * 60 * 24
+}
+
+/// Configuration driving automatic ACME v2 (e.g. Let's Encrypt) certificate
+/// provisioning for the host embedded in `server_url`.
+#[derive(Debug, Deserialize, Clone)]
+struct RawAcmeConfig {
+    contact_email: String,
+    #[serde(default)]
+    staging: bool,
+    #[serde(default = "default_acme_cert_path")]
+    cert_path: String,
+    #[serde(default = "default_acme_key_path")]
+    key_path: String,
+    #[serde(default = "default_acme_renew_interval_seconds")]
+    renew_interval_seconds: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    pub host: String,
+    pub contact_email: String,
+    pub directory_url: String,
+    pub cert_path: String,
+    pub key_path: String,
+    pub renew_interval: Duration,
+}
+
+/// Configuration for delegating authentication to an external OpenID Connect
+/// provider instead of auto-asserting the statically configured attributes.
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    /// Maps ID-token claim names onto the attribute names released in the `AuthResult`.
+    pub claim_mapping: HashMap<String, String>,
+}
+
+fn default_session_ttl_seconds() -> u64 {
+    300
+}
+
+fn default_session_reap_interval_seconds() -> u64 {
+    60
+}
+
+fn default_delivery_attempts() -> u32 {
+    3
+}
+
+fn default_delivery_base_delay_ms() -> u64 {
+    200
+}
 
 #[derive(Debug)]
 pub enum Error {
     UnknownAttribute(String),
+    InsufficientAssurance(String),
+    InvalidAcmeHost(String),
+    MissingOidcClaim(String),
     YamlError(serde_yaml::Error),
     Json(serde_json::Error),
     JWT(id_contact_jwe::Error),
@@ -36,6 +148,18 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::UnknownAttribute(a) => f.write_fmt(format_args!("Unknown attribute {}", a)),
+            Error::InsufficientAssurance(a) => f.write_fmt(format_args!(
+                "Attribute {} requires a higher assurance level than was confirmed",
+                a
+            )),
+            Error::InvalidAcmeHost(url) => f.write_fmt(format_args!(
+                "server_url {} has no parseable host, required for ACME provisioning",
+                url
+            )),
+            Error::MissingOidcClaim(claim) => f.write_fmt(format_args!(
+                "upstream ID token did not include claim {}",
+                claim
+            )),
             Error::YamlError(e) => e.fmt(f),
             Error::Json(e) => e.fmt(f),
             Error::JWT(e) => e.fmt(f),
@@ -57,8 +181,28 @@ impl StdError for Error {
 #[derive(Deserialize, Debug)]
 struct RawConfig {
     server_url: String,
-    attributes: HashMap<String, String>,
+    #[serde(default)]
+    internal_url: Option<String>,
+    attributes: HashMap<String, AttributeConfig>,
     with_session: bool,
+    #[serde(default)]
+    pin: Option<String>,
+    #[serde(default = "default_session_ttl_seconds")]
+    session_ttl_seconds: u64,
+    #[serde(default = "default_session_reap_interval_seconds")]
+    session_reap_interval_seconds: u64,
+    #[serde(default = "default_delivery_attempts")]
+    delivery_attempts: u32,
+    #[serde(default = "default_delivery_base_delay_ms")]
+    delivery_base_delay_ms: u64,
+    #[serde(default)]
+    outcome_override: Option<OutcomeOverride>,
+    #[serde(default)]
+    failing_attributes: HashSet<String>,
+    #[serde(default)]
+    oidc: Option<OidcConfig>,
+    #[serde(default)]
+    acme: Option<RawAcmeConfig>,
     encryption_pubkey: EncryptionKeyConfig,
     signing_privkey: SignKeyConfig,
 }
@@ -67,8 +211,18 @@ struct RawConfig {
 #[serde(try_from = "RawConfig")]
 pub struct Config {
     server_url: String,
-    attributes: HashMap<String, String>,
+    internal_url: String,
+    attributes: HashMap<String, AttributeConfig>,
     with_session: bool,
+    pin: Option<String>,
+    session_ttl: Duration,
+    session_reap_interval: Duration,
+    delivery_attempts: u32,
+    delivery_base_delay: Duration,
+    outcome_override: Option<OutcomeOverride>,
+    failing_attributes: HashSet<String>,
+    oidc: Option<OidcConfig>,
+    acme: Option<AcmeConfig>,
     encrypter: Box<dyn JweEncrypter>,
     signer: Box<dyn JwsSigner>,
 }
@@ -77,10 +231,42 @@ pub struct Config {
 impl TryFrom<RawConfig> for Config {
     type Error = Error;
     fn try_from(config: RawConfig) -> Result<Config, Error> {
+        let acme = config
+            .acme
+            .map(|raw| {
+                let host = reqwest::Url::parse(&config.server_url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .ok_or_else(|| Error::InvalidAcmeHost(config.server_url.clone()))?;
+                Ok(AcmeConfig {
+                    host,
+                    contact_email: raw.contact_email,
+                    directory_url: if raw.staging {
+                        LETS_ENCRYPT_STAGING_DIRECTORY.to_string()
+                    } else {
+                        LETS_ENCRYPT_PRODUCTION_DIRECTORY.to_string()
+                    },
+                    cert_path: raw.cert_path,
+                    key_path: raw.key_path,
+                    renew_interval: Duration::from_secs(raw.renew_interval_seconds),
+                })
+            })
+            .transpose()?;
+
         Ok(Config {
+            internal_url: config.internal_url.unwrap_or_else(|| config.server_url.clone()),
             server_url: config.server_url,
             attributes: config.attributes,
             with_session: config.with_session,
+            pin: config.pin,
+            session_ttl: Duration::from_secs(config.session_ttl_seconds),
+            session_reap_interval: Duration::from_secs(config.session_reap_interval_seconds),
+            delivery_attempts: config.delivery_attempts,
+            delivery_base_delay: Duration::from_millis(config.delivery_base_delay_ms),
+            outcome_override: config.outcome_override,
+            failing_attributes: config.failing_attributes,
+            oidc: config.oidc,
+            acme,
             encrypter: Box::<dyn JweEncrypter>::try_from(config.encryption_pubkey)?,
             signer: Box::<dyn JwsSigner>::try_from(config.signing_privkey)?,
         })
@@ -96,23 +282,134 @@ impl Config {
         Ok(())
     }
 
-    pub fn map_attributes(&self, attributes: &[String]) -> Result<HashMap<String, String>, Error> {
-        let mut result: HashMap<String, String> = HashMap::new();
-        for attribute in attributes.iter() {
-            result.insert(attribute.clone(), self.attributes.get(attribute).ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?.clone());
-        }
+    /// Computes the highest assurance level required to release `attributes`.
+    pub fn required_assurance(&self, attributes: &[String]) -> AssuranceLevel {
+        required_assurance(&self.attributes, attributes)
+    }
 
-        Ok(result)
+    /// Maps the requested attributes onto their configured values, refusing
+    /// to assert any attribute whose assurance requirement exceeds what the
+    /// confirmation factors completed so far (`completed_assurance`) satisfy.
+    pub fn map_attributes(
+        &self,
+        attributes: &[String],
+        completed_assurance: AssuranceLevel,
+    ) -> Result<HashMap<String, String>, Error> {
+        map_attributes(&self.attributes, attributes, completed_assurance)
+    }
+
+    /// Checks a submitted PIN against the configured test PIN. Used to gate
+    /// the release of attributes above `Low` assurance behind a second factor.
+    pub fn verify_pin(&self, submitted: &str) -> bool {
+        match &self.pin {
+            Some(pin) if pin.len() == submitted.len() => {
+                pin.as_bytes().ct_eq(submitted.as_bytes()).into()
+            }
+            _ => false,
+        }
     }
 
     pub fn server_url(&self) -> &str {
         &self.server_url
     }
 
+    pub fn internal_url(&self) -> &str {
+        &self.internal_url
+    }
+
     pub fn with_session(&self) -> bool {
         self.with_session
     }
 
+    pub fn session_ttl(&self) -> Duration {
+        self.session_ttl
+    }
+
+    pub fn session_reap_interval(&self) -> Duration {
+        self.session_reap_interval
+    }
+
+    pub fn delivery_attempts(&self) -> u32 {
+        self.delivery_attempts
+    }
+
+    pub fn delivery_base_delay(&self) -> Duration {
+        self.delivery_base_delay
+    }
+
+    /// Determines the `AuthStatus` to assert for the given requested attributes,
+    /// honouring a global `outcome_override` or any attribute configured in
+    /// `failing_attributes`. Defaults to `Success`.
+    pub fn simulated_status(&self, attributes: &[String]) -> AuthStatus {
+        match self.outcome_override {
+            Some(OutcomeOverride::Failed) => return AuthStatus::Failed,
+            Some(OutcomeOverride::Success) => return AuthStatus::Success,
+            None => {}
+        }
+
+        if attributes.iter().any(|a| self.failing_attributes.contains(a)) {
+            return AuthStatus::Failed;
+        }
+
+        AuthStatus::Success
+    }
+
+    pub fn oidc(&self) -> Option<&OidcConfig> {
+        self.oidc.as_ref()
+    }
+
+    /// Projects verified ID-token claims onto attribute names using the
+    /// configured `oidc.claim_mapping`, mirroring how `map_attributes` projects
+    /// the static attribute table: every requested attribute must resolve to a
+    /// value or the whole call fails, rather than silently asserting
+    /// `Success` with attributes missing. Fails with `Error::UnknownAttribute`
+    /// if `attribute` has no entry in `claim_mapping`, `Error::MissingOidcClaim`
+    /// if the ID token didn't carry the mapped claim, and
+    /// `Error::InsufficientAssurance` if the attribute's configured assurance
+    /// exceeds `completed_assurance` — so a PIN gate configured alongside OIDC
+    /// delegation is not bypassable by skipping it.
+    pub fn map_oidc_claims(
+        &self,
+        claims: &serde_json::Value,
+        attributes: &[String],
+        completed_assurance: AssuranceLevel,
+    ) -> Result<HashMap<String, String>, Error> {
+        let claim_mapping = self.oidc.as_ref().map(|oidc| &oidc.claim_mapping);
+
+        let mut result = HashMap::new();
+        for attribute in attributes {
+            let claim_name = claim_mapping
+                .and_then(|mapping| {
+                    mapping
+                        .iter()
+                        .find(|(_, attribute_name)| *attribute_name == attribute)
+                })
+                .map(|(claim_name, _)| claim_name.as_str())
+                .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?;
+
+            let required = self
+                .attributes
+                .get(attribute)
+                .map(|entry| entry.assurance)
+                .unwrap_or_default();
+            if required > completed_assurance {
+                return Err(Error::InsufficientAssurance(attribute.clone()));
+            }
+
+            let value = claims
+                .get(claim_name)
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| Error::MissingOidcClaim(claim_name.to_string()))?;
+            result.insert(attribute.clone(), value.to_string());
+        }
+
+        Ok(result)
+    }
+
+    pub fn acme(&self) -> Option<&AcmeConfig> {
+        self.acme.as_ref()
+    }
+
     pub fn encrypter(&self) -> &dyn JweEncrypter {
         self.encrypter.as_ref()
     }
@@ -129,3 +426,86 @@ impl Config {
         Ok(serde_yaml::from_reader(reader)?)
     }
 }
+
+fn required_assurance(
+    attributes_config: &HashMap<String, AttributeConfig>,
+    attributes: &[String],
+) -> AssuranceLevel {
+    attributes
+        .iter()
+        .filter_map(|a| attributes_config.get(a))
+        .map(|entry| entry.assurance)
+        .max()
+        .unwrap_or_default()
+}
+
+fn map_attributes(
+    attributes_config: &HashMap<String, AttributeConfig>,
+    attributes: &[String],
+    completed_assurance: AssuranceLevel,
+) -> Result<HashMap<String, String>, Error> {
+    let mut result: HashMap<String, String> = HashMap::new();
+    for attribute in attributes.iter() {
+        let entry = attributes_config
+            .get(attribute)
+            .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?;
+        if entry.assurance > completed_assurance {
+            return Err(Error::InsufficientAssurance(attribute.clone()));
+        }
+        result.insert(attribute.clone(), entry.value.clone());
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes_config() -> HashMap<String, AttributeConfig> {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            "email".to_string(),
+            AttributeConfig {
+                value: "user@example.com".to_string(),
+                assurance: AssuranceLevel::Low,
+            },
+        );
+        attributes.insert(
+            "bsn".to_string(),
+            AttributeConfig {
+                value: "123456789".to_string(),
+                assurance: AssuranceLevel::High,
+            },
+        );
+        attributes
+    }
+
+    #[test]
+    fn required_assurance_is_the_max_over_requested_attributes() {
+        let attributes = attributes_config();
+        assert_eq!(
+            required_assurance(&attributes, &["email".to_string()]),
+            AssuranceLevel::Low
+        );
+        assert_eq!(
+            required_assurance(&attributes, &["email".to_string(), "bsn".to_string()]),
+            AssuranceLevel::High
+        );
+    }
+
+    #[test]
+    fn map_attributes_refuses_attribute_above_completed_assurance() {
+        let attributes = attributes_config();
+        let result = map_attributes(&attributes, &["bsn".to_string()], AssuranceLevel::Low);
+        assert!(matches!(result, Err(Error::InsufficientAssurance(a)) if a == "bsn"));
+    }
+
+    #[test]
+    fn map_attributes_releases_attribute_at_sufficient_assurance() {
+        let attributes = attributes_config();
+        let result = map_attributes(&attributes, &["bsn".to_string()], AssuranceLevel::High)
+            .expect("high assurance satisfies the bsn requirement");
+        assert_eq!(result.get("bsn").map(String::as_str), Some("123456789"));
+    }
+}
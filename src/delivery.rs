@@ -0,0 +1,176 @@
+use rocket::tokio::time::sleep;
+use std::time::Duration;
+
+/// Outcome of attempting to deliver a signed `AuthResult` to an `attr_url`.
+#[derive(Debug)]
+pub enum DeliveryError {
+    Transport(reqwest::Error),
+    Status { status: reqwest::StatusCode, body: String },
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::Transport(e) => write!(f, "transport error: {}", e),
+            DeliveryError::Status { status, body } => {
+                write!(f, "unexpected status {}: {}", status, body)
+            }
+        }
+    }
+}
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+}
+
+/// POSTs `body` to `attr_url` as a `application/jwt` payload, retrying transport
+/// failures and retryable 5xx responses with exponential backoff. Gives up
+/// after `attempts` tries and returns the last observed error.
+pub async fn deliver_auth_result(
+    client: &reqwest::Client,
+    attr_url: &str,
+    body: String,
+    attempts: u32,
+    base_delay: Duration,
+) -> Result<(), DeliveryError> {
+    let mut delay = base_delay;
+    let mut last_error = None;
+
+    for attempt in 1..=attempts.max(1) {
+        let result = client
+            .post(attr_url)
+            .header("Content-Type", "application/jwt")
+            .body(body.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+
+                let response_body = response.text().await.unwrap_or_default();
+                println!(
+                    "Attempt {}/{} reporting result to {} failed with status {}: {}",
+                    attempt, attempts, attr_url, status, response_body
+                );
+
+                let error = DeliveryError::Status {
+                    status,
+                    body: response_body,
+                };
+                if !is_retryable_status(status) {
+                    return Err(error);
+                }
+                last_error = Some(error);
+            }
+            Err(e) => {
+                println!(
+                    "Attempt {}/{} reporting result to {} failed: {}",
+                    attempt, attempts, attr_url, e
+                );
+                last_error = Some(DeliveryError::Transport(e));
+            }
+        }
+
+        if attempt < attempts {
+            sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    Err(last_error.expect("at least one delivery attempt is always made"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn server_errors_are_retryable_client_errors_are_not() {
+        assert!(is_retryable_status(reqwest::StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable_status(reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_retryable_status(reqwest::StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(reqwest::StatusCode::OK));
+    }
+
+    /// Accepts one connection on `listener`, reads (and discards) the
+    /// request, and writes back `status_line` with an empty body.
+    async fn respond_once(listener: &TcpListener, status_line: &str) {
+        let (mut socket, _) = listener.accept().await.expect("mock server accept");
+        let mut buf = [0u8; 1024];
+        let _ = socket.read(&mut buf).await;
+        let response = format!("{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status_line);
+        socket
+            .write_all(response.as_bytes())
+            .await
+            .expect("mock server write");
+    }
+
+    #[rocket::tokio::test]
+    async fn gives_up_after_configured_attempts_with_doubling_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = rocket::tokio::spawn(async move {
+            for _ in 0..3 {
+                respond_once(&listener, "HTTP/1.1 503 Service Unavailable").await;
+            }
+        });
+
+        let client = reqwest::Client::new();
+        let started = std::time::Instant::now();
+        let result = deliver_auth_result(&client, &url, "body".to_string(), 3, Duration::from_millis(20)).await;
+        let elapsed = started.elapsed();
+
+        server.await.expect("mock server task");
+        assert!(matches!(
+            result,
+            Err(DeliveryError::Status { status, .. }) if status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+        ));
+        // Two sleeps between three attempts, doubling from the 20ms base delay: 20ms + 40ms.
+        assert!(elapsed >= Duration::from_millis(60));
+    }
+
+    #[rocket::tokio::test]
+    async fn stops_retrying_once_a_later_attempt_succeeds() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = rocket::tokio::spawn(async move {
+            respond_once(&listener, "HTTP/1.1 503 Service Unavailable").await;
+            respond_once(&listener, "HTTP/1.1 200 OK").await;
+        });
+
+        let client = reqwest::Client::new();
+        let result = deliver_auth_result(&client, &url, "body".to_string(), 3, Duration::from_millis(5)).await;
+
+        server.await.expect("mock server task");
+        assert!(result.is_ok());
+    }
+
+    #[rocket::tokio::test]
+    async fn does_not_retry_non_retryable_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let server = rocket::tokio::spawn(async move {
+            respond_once(&listener, "HTTP/1.1 400 Bad Request").await;
+        });
+
+        let client = reqwest::Client::new();
+        let result = deliver_auth_result(&client, &url, "body".to_string(), 3, Duration::from_millis(5)).await;
+
+        server.await.expect("mock server task");
+        assert!(matches!(
+            result,
+            Err(DeliveryError::Status { status, .. }) if status == reqwest::StatusCode::BAD_REQUEST
+        ));
+    }
+}
@@ -6,8 +6,9 @@ use id_contact_proto::{
     AuthResult, AuthStatus, SessionActivity, StartAuthRequest, StartAuthResponse,
 };
 use rocket::{
-    form::FromForm,
+    form::{Form, FromForm},
     get, launch, post,
+    http::Status,
     response::{content::Html, Redirect},
     routes,
     serde::json::Json,
@@ -15,7 +16,17 @@ use rocket::{
 };
 use std::{error::Error as StdError, fmt::Display};
 
+mod acme;
+mod assurance;
 mod config;
+mod delivery;
+mod oidc;
+mod session;
+
+use acme::ChallengeStore;
+use assurance::AssuranceStore;
+use oidc::{OidcStateStore, PendingAuth};
+use session::SessionStore;
 
 #[derive(Debug)]
 enum Error {
@@ -25,6 +36,9 @@ enum Error {
     Json(serde_json::Error),
     Utf(std::str::Utf8Error),
     Jwt(id_contact_jwt::Error),
+    Oidc(oidc::Error),
+    UnknownOidcState,
+    OidcNotConfigured,
 }
 
 impl<'r, 'o: 'r> rocket::response::Responder<'r, 'o> for Error {
@@ -70,6 +84,12 @@ impl From<id_contact_jwt::Error> for Error {
     }
 }
 
+impl From<oidc::Error> for Error {
+    fn from(e: oidc::Error) -> Error {
+        Error::Oidc(e)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -79,6 +99,9 @@ impl Display for Error {
             Error::Utf(e) => e.fmt(f),
             Error::Json(e) => e.fmt(f),
             Error::Jwt(e) => e.fmt(f),
+            Error::Oidc(e) => e.fmt(f),
+            Error::UnknownOidcState => f.write_str("unknown or expired OIDC state"),
+            Error::OidcNotConfigured => f.write_str("OIDC delegation is not configured"),
         }
     }
 }
@@ -92,6 +115,9 @@ impl StdError for Error {
             Error::Utf(e) => Some(e),
             Error::Json(e) => Some(e),
             Error::Jwt(e) => Some(e),
+            Error::Oidc(e) => Some(e),
+            Error::UnknownOidcState => None,
+            Error::OidcNotConfigured => None,
         }
     }
 }
@@ -100,6 +126,19 @@ impl StdError for Error {
 #[template(path = "confirm_auth.html")]
 struct ConfirmTemplate<'a> {
     dologin: &'a str,
+    docancel: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "confirm_pin.html")]
+struct ConfirmPinTemplate<'a> {
+    doverify: &'a str,
+    error: Option<&'a str>,
+}
+
+#[derive(FromForm, Debug)]
+struct PinData {
+    pin: String,
 }
 
 #[derive(FromForm, Debug)]
@@ -115,9 +154,39 @@ async fn confirm_oob(
     continuation: String,
     attr_url: String,
 ) -> Result<Html<String>, Error> {
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+
+    if config.required_assurance(&requested_attributes) > config::AssuranceLevel::Low {
+        let template = ConfirmPinTemplate {
+            doverify: &format!(
+                "{}/confirm/pin/{}/{}/{}",
+                config.server_url(),
+                attributes,
+                continuation,
+                attr_url
+            ),
+            error: None,
+        };
+        return Ok(Html(template.render()?));
+    }
+
+    let login_path = if config.oidc().is_some() {
+        "oidc/start"
+    } else {
+        "browser"
+    };
     let template = ConfirmTemplate {
         dologin: &format!(
-            "{}/browser/{}/{}/{}",
+            "{}/{}/{}/{}/{}",
+            config.server_url(),
+            login_path,
+            attributes,
+            continuation,
+            attr_url
+        ),
+        docancel: &format!(
+            "{}/browser/{}/{}/{}/cancel",
             config.server_url(),
             attributes,
             continuation,
@@ -134,9 +203,154 @@ async fn confirm_ib(
     attributes: String,
     continuation: String,
 ) -> Result<Html<String>, Error> {
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+
+    if config.required_assurance(&requested_attributes) > config::AssuranceLevel::Low {
+        let template = ConfirmPinTemplate {
+            doverify: &format!(
+                "{}/confirm/pin/{}/{}",
+                config.server_url(),
+                attributes,
+                continuation
+            ),
+            error: None,
+        };
+        return Ok(Html(template.render()?));
+    }
+
+    let login_path = if config.oidc().is_some() {
+        "oidc/start"
+    } else {
+        "browser"
+    };
+    let template = ConfirmTemplate {
+        dologin: &format!(
+            "{}/{}/{}/{}",
+            config.server_url(),
+            login_path,
+            attributes,
+            continuation
+        ),
+        docancel: &format!(
+            "{}/browser/{}/{}/cancel",
+            config.server_url(),
+            attributes,
+            continuation
+        ),
+    };
+    let output = template.render()?;
+    Ok(Html(output))
+}
+
+#[post("/confirm/pin/<attributes>/<continuation>/<attr_url>", data = "<form>")]
+async fn verify_pin_oob(
+    config: &State<config::Config>,
+    assurance_store: &State<AssuranceStore>,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    form: Form<PinData>,
+) -> Result<Html<String>, Error> {
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+    let required = config.required_assurance(&requested_attributes);
+
+    if !config.verify_pin(&form.pin) {
+        let template = ConfirmPinTemplate {
+            doverify: &format!(
+                "{}/confirm/pin/{}/{}/{}",
+                config.server_url(),
+                attributes,
+                continuation,
+                attr_url
+            ),
+            error: Some("Incorrect PIN"),
+        };
+        return Ok(Html(template.render()?));
+    }
+
+    let assurance_token = assurance_store
+        .issue(
+            attributes.clone(),
+            continuation.clone(),
+            Some(attr_url.clone()),
+            required,
+        )
+        .await;
+
+    let login_path = if config.oidc().is_some() {
+        "oidc/start"
+    } else {
+        "browser"
+    };
+    let template = ConfirmTemplate {
+        dologin: &format!(
+            "{}/{}/{}/{}/{}?assurance_token={}",
+            config.server_url(),
+            login_path,
+            attributes,
+            continuation,
+            attr_url,
+            assurance_token
+        ),
+        docancel: &format!(
+            "{}/browser/{}/{}/{}/cancel",
+            config.server_url(),
+            attributes,
+            continuation,
+            attr_url
+        ),
+    };
+    let output = template.render()?;
+    Ok(Html(output))
+}
+
+#[post("/confirm/pin/<attributes>/<continuation>", data = "<form>")]
+async fn verify_pin_ib(
+    config: &State<config::Config>,
+    assurance_store: &State<AssuranceStore>,
+    attributes: String,
+    continuation: String,
+    form: Form<PinData>,
+) -> Result<Html<String>, Error> {
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+    let required = config.required_assurance(&requested_attributes);
+
+    if !config.verify_pin(&form.pin) {
+        let template = ConfirmPinTemplate {
+            doverify: &format!(
+                "{}/confirm/pin/{}/{}",
+                config.server_url(),
+                attributes,
+                continuation
+            ),
+            error: Some("Incorrect PIN"),
+        };
+        return Ok(Html(template.render()?));
+    }
+
+    let assurance_token = assurance_store
+        .issue(attributes.clone(), continuation.clone(), None, required)
+        .await;
+
+    let login_path = if config.oidc().is_some() {
+        "oidc/start"
+    } else {
+        "browser"
+    };
     let template = ConfirmTemplate {
         dologin: &format!(
-            "{}/browser/{}/{}",
+            "{}/{}/{}/{}?assurance_token={}",
+            config.server_url(),
+            login_path,
+            attributes,
+            continuation,
+            assurance_token
+        ),
+        docancel: &format!(
+            "{}/browser/{}/{}/cancel",
             config.server_url(),
             attributes,
             continuation
@@ -146,26 +360,43 @@ async fn confirm_ib(
     Ok(Html(output))
 }
 
-#[post("/session/update?<typedata..>")]
-async fn session_update(typedata: SessionUpdateData) {
-    println!("Session update received: {:?}", typedata.typeval);
+#[post("/session/<id>/update?<typedata..>")]
+async fn session_update(sessions: &State<SessionStore>, id: String, typedata: SessionUpdateData) -> Status {
+    match sessions.update(&id, typedata.typeval).await {
+        Some(()) => Status::Ok,
+        None => Status::NotFound,
+    }
 }
 
-#[get("/browser/<attributes>/<continuation>/<attr_url>")]
+#[get("/browser/<attributes>/<continuation>/<attr_url>?<assurance_token>")]
 async fn user_oob(
     config: &State<config::Config>,
+    sessions: &State<SessionStore>,
+    assurance_store: &State<AssuranceStore>,
     attributes: String,
     continuation: String,
     attr_url: String,
+    assurance_token: Option<String>,
 ) -> Result<Redirect, Error> {
-    let attributes = base64::decode_config(attributes, URL_SAFE_NO_PAD)?;
-    let attributes: Vec<String> = serde_json::from_slice(&attributes)?;
-    let attributes = config.map_attributes(&attributes)?;
+    let completed_assurance = assurance_store
+        .consume(
+            assurance_token.as_deref(),
+            &attributes,
+            &continuation,
+            Some(&attr_url),
+        )
+        .await;
+
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+    let status = config.simulated_status(&requested_attributes);
+    let attributes = config.map_attributes(&requested_attributes, completed_assurance)?;
     let auth_result = AuthResult {
-        status: AuthStatus::Success,
+        status,
         attributes: Some(attributes),
         session_url: if config.with_session() {
-            Some(format!("{}/session/update", config.internal_url()))
+            let id = sessions.create().await;
+            Some(format!("{}/session/{}/update", config.internal_url(), id))
         } else {
             None
         },
@@ -180,37 +411,85 @@ async fn user_oob(
     let attr_url = std::str::from_utf8(&attr_url)?;
 
     let client = reqwest::Client::new();
-    let result = client
-        .post(attr_url)
-        .header("Content-Type", "application/jwt")
-        .body(auth_result.clone())
-        .send()
-        .await;
-    if let Err(e) = result {
-        // Log only
-        println!("Failure reporting results: {}", e);
-    } else {
-        println!("Reported result jwe {} to {}", &auth_result, attr_url);
+    match delivery::deliver_auth_result(
+        &client,
+        attr_url,
+        auth_result.clone(),
+        config.delivery_attempts(),
+        config.delivery_base_delay(),
+    )
+    .await
+    {
+        Ok(()) => println!("Reported result jwe {} to {}", &auth_result, attr_url),
+        Err(e) => println!("Giving up reporting result to {}: {}", attr_url, e),
     }
 
     println!("Redirecting user to {}", continuation);
     Ok(Redirect::to(continuation.to_string()))
 }
 
-#[get("/browser/<attributes>/<continuation>")]
+#[get("/browser/<_attributes>/<continuation>/<attr_url>/cancel")]
+async fn user_oob_cancel(
+    config: &State<config::Config>,
+    _attributes: String,
+    continuation: String,
+    attr_url: String,
+) -> Result<Redirect, Error> {
+    let auth_result = AuthResult {
+        status: AuthStatus::Failed,
+        attributes: None,
+        session_url: None,
+    };
+    let auth_result =
+        sign_and_encrypt_auth_result(&auth_result, config.signer(), config.encrypter())?;
+
+    let continuation = base64::decode_config(continuation, URL_SAFE_NO_PAD)?;
+    let continuation = std::str::from_utf8(&continuation)?;
+
+    let attr_url = base64::decode_config(attr_url, URL_SAFE_NO_PAD)?;
+    let attr_url = std::str::from_utf8(&attr_url)?;
+
+    let client = reqwest::Client::new();
+    match delivery::deliver_auth_result(
+        &client,
+        attr_url,
+        auth_result.clone(),
+        config.delivery_attempts(),
+        config.delivery_base_delay(),
+    )
+    .await
+    {
+        Ok(()) => println!("Reported cancellation jwe {} to {}", &auth_result, attr_url),
+        Err(e) => println!("Giving up reporting cancellation to {}: {}", attr_url, e),
+    }
+
+    println!("Redirecting user to {}", continuation);
+    Ok(Redirect::to(continuation.to_string()))
+}
+
+#[get("/browser/<attributes>/<continuation>?<assurance_token>")]
 async fn user_inline(
     config: &State<config::Config>,
+    sessions: &State<SessionStore>,
+    assurance_store: &State<AssuranceStore>,
     attributes: String,
     continuation: String,
+    assurance_token: Option<String>,
 ) -> Result<Redirect, Error> {
-    let attributes = base64::decode_config(attributes, URL_SAFE_NO_PAD)?;
-    let attributes: Vec<String> = serde_json::from_slice(&attributes)?;
-    let attributes = config.map_attributes(&attributes)?;
+    let completed_assurance = assurance_store
+        .consume(assurance_token.as_deref(), &attributes, &continuation, None)
+        .await;
+
+    let requested_attributes = base64::decode_config(&attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+    let status = config.simulated_status(&requested_attributes);
+    let attributes = config.map_attributes(&requested_attributes, completed_assurance)?;
     let auth_result = AuthResult {
-        status: AuthStatus::Success,
+        status,
         attributes: Some(attributes),
         session_url: if config.with_session() {
-            Some(format!("{}/session/update", config.internal_url()))
+            let id = sessions.create().await;
+            Some(format!("{}/session/{}/update", config.internal_url(), id))
         } else {
             None
         },
@@ -238,6 +517,173 @@ async fn user_inline(
     }
 }
 
+#[get("/browser/<_attributes>/<continuation>/cancel")]
+async fn user_inline_cancel(
+    config: &State<config::Config>,
+    _attributes: String,
+    continuation: String,
+) -> Result<Redirect, Error> {
+    let auth_result = AuthResult {
+        status: AuthStatus::Failed,
+        attributes: None,
+        session_url: None,
+    };
+    let auth_result =
+        sign_and_encrypt_auth_result(&auth_result, config.signer(), config.encrypter())?;
+
+    let continuation = base64::decode_config(continuation, URL_SAFE_NO_PAD)?;
+    let continuation = std::str::from_utf8(&continuation)?;
+
+    println!(
+        "Redirecting user to {} with cancellation result {}",
+        continuation, &auth_result
+    );
+    if continuation.contains('?') {
+        Ok(Redirect::to(format!(
+            "{}&result={}",
+            continuation, auth_result
+        )))
+    } else {
+        Ok(Redirect::to(format!(
+            "{}?result={}",
+            continuation, auth_result
+        )))
+    }
+}
+
+#[get("/oidc/start/<attributes>/<continuation>/<attr_url>?<assurance_token>")]
+async fn oidc_start_oob(
+    config: &State<config::Config>,
+    oidc_states: &State<OidcStateStore>,
+    assurance_store: &State<AssuranceStore>,
+    attributes: String,
+    continuation: String,
+    attr_url: String,
+    assurance_token: Option<String>,
+) -> Result<Redirect, Error> {
+    let oidc_config = config.oidc().ok_or(Error::OidcNotConfigured)?;
+
+    let completed_assurance = assurance_store
+        .consume(
+            assurance_token.as_deref(),
+            &attributes,
+            &continuation,
+            Some(&attr_url),
+        )
+        .await;
+
+    let requested_attributes = base64::decode_config(attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+
+    let continuation = base64::decode_config(continuation, URL_SAFE_NO_PAD)?;
+    let continuation = std::str::from_utf8(&continuation)?.to_string();
+
+    let attr_url = base64::decode_config(attr_url, URL_SAFE_NO_PAD)?;
+    let attr_url = std::str::from_utf8(&attr_url)?.to_string();
+
+    let nonce = oidc::new_nonce();
+    let state = oidc_states
+        .start(PendingAuth {
+            attributes: requested_attributes,
+            continuation,
+            attr_url: Some(attr_url),
+            nonce: nonce.clone(),
+            assurance: completed_assurance,
+        })
+        .await;
+
+    let client = reqwest::Client::new();
+    let redirect_uri = format!("{}/oidc/callback", config.server_url());
+    let authorization_url = oidc::authorization_url(oidc_config, &client, &redirect_uri, &state, &nonce).await?;
+
+    Ok(Redirect::to(authorization_url))
+}
+
+#[get("/oidc/start/<attributes>/<continuation>?<assurance_token>")]
+async fn oidc_start_ib(
+    config: &State<config::Config>,
+    oidc_states: &State<OidcStateStore>,
+    assurance_store: &State<AssuranceStore>,
+    attributes: String,
+    continuation: String,
+    assurance_token: Option<String>,
+) -> Result<Redirect, Error> {
+    let oidc_config = config.oidc().ok_or(Error::OidcNotConfigured)?;
+
+    let completed_assurance = assurance_store
+        .consume(assurance_token.as_deref(), &attributes, &continuation, None)
+        .await;
+
+    let requested_attributes = base64::decode_config(attributes, URL_SAFE_NO_PAD)?;
+    let requested_attributes: Vec<String> = serde_json::from_slice(&requested_attributes)?;
+
+    let continuation = base64::decode_config(continuation, URL_SAFE_NO_PAD)?;
+    let continuation = std::str::from_utf8(&continuation)?.to_string();
+
+    let nonce = oidc::new_nonce();
+    let state = oidc_states
+        .start(PendingAuth {
+            attributes: requested_attributes,
+            continuation,
+            attr_url: None,
+            nonce: nonce.clone(),
+            assurance: completed_assurance,
+        })
+        .await;
+
+    let client = reqwest::Client::new();
+    let redirect_uri = format!("{}/oidc/callback", config.server_url());
+    let authorization_url = oidc::authorization_url(oidc_config, &client, &redirect_uri, &state, &nonce).await?;
+
+    Ok(Redirect::to(authorization_url))
+}
+
+#[get("/oidc/callback?<code>&<state>")]
+async fn oidc_callback(
+    config: &State<config::Config>,
+    oidc_states: &State<OidcStateStore>,
+    code: String,
+    state: String,
+) -> Result<Redirect, Error> {
+    let oidc_config = config.oidc().ok_or(Error::OidcNotConfigured)?;
+    let pending = oidc_states.take(&state).await.ok_or(Error::UnknownOidcState)?;
+
+    let client = reqwest::Client::new();
+    let redirect_uri = format!("{}/oidc/callback", config.server_url());
+    let claims = oidc::exchange_and_validate(oidc_config, &client, &redirect_uri, &code, &pending.nonce).await?;
+
+    let attributes = config.map_oidc_claims(&claims, &pending.attributes, pending.assurance)?;
+    let auth_result = AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(attributes),
+        session_url: None,
+    };
+    let auth_result =
+        sign_and_encrypt_auth_result(&auth_result, config.signer(), config.encrypter())?;
+
+    if let Some(attr_url) = &pending.attr_url {
+        match delivery::deliver_auth_result(
+            &client,
+            attr_url,
+            auth_result.clone(),
+            config.delivery_attempts(),
+            config.delivery_base_delay(),
+        )
+        .await
+        {
+            Ok(()) => println!("Reported result jwe {} to {}", &auth_result, attr_url),
+            Err(e) => println!("Giving up reporting result to {}: {}", attr_url, e),
+        }
+
+        println!("Redirecting user to {}", pending.continuation);
+        Ok(Redirect::to(pending.continuation))
+    } else if pending.continuation.contains('?') {
+        Ok(Redirect::to(format!("{}&result={}", pending.continuation, auth_result)))
+    } else {
+        Ok(Redirect::to(format!("{}?result={}", pending.continuation, auth_result)))
+    }
+}
+
 #[post("/start_authentication", data = "<request>")]
 async fn start_authentication(
     config: &State<config::Config>,
@@ -275,22 +721,67 @@ async fn start_authentication(
 
 #[launch]
 fn rocket() -> _ {
-    let base = rocket::build().mount(
+    let figment = rocket::Config::figment();
+    let config = figment.extract::<Config>().unwrap_or_else(|_e| {
+        // Drop error value, as it could contain secrets
+        panic!("Failure to parse configuration")
+    });
+
+    // Provisioned certificates land at `acme.cert_path`/`acme.key_path`; hand
+    // those to Rocket's own TLS config so the provisioned cert is what we
+    // actually serve, and renewals (which overwrite the same paths) take
+    // effect on the next restart. On a fresh deployment those files don't
+    // exist yet, so make sure there is at least a bootstrap certificate for
+    // Rocket to bind to before `acme::provisioning_fairing` can run.
+    let figment = match config.acme() {
+        Some(acme) => {
+            acme::ensure_bootstrap_certificate(acme).unwrap_or_else(|e| {
+                panic!("Failed to provision a bootstrap TLS certificate: {}", e)
+            });
+            figment.merge((
+                "tls",
+                rocket::config::TlsConfig::from_paths(&acme.cert_path, &acme.key_path),
+            ))
+        }
+        None => figment,
+    };
+
+    let base = rocket::custom(figment).mount(
         "/",
         routes![
             start_authentication,
             user_inline,
+            user_inline_cancel,
             user_oob,
+            user_oob_cancel,
             session_update,
             confirm_oob,
-            confirm_ib
+            confirm_ib,
+            verify_pin_oob,
+            verify_pin_ib,
+            oidc_start_oob,
+            oidc_start_ib,
+            oidc_callback,
+            acme::http01_challenge
         ],
     );
 
-    let config = base.figment().extract::<Config>().unwrap_or_else(|_e| {
-        // Drop error value, as it could contain secrets
-        panic!("Failure to parse configuration")
-    });
+    let reaper = session::reaper_fairing(config.session_ttl(), config.session_reap_interval());
+    let acme_config = config.acme().cloned();
 
-    base.manage(config)
+    let rocket = base
+        .manage(SessionStore::new())
+        .manage(OidcStateStore::new())
+        .manage(AssuranceStore::new())
+        .manage(ChallengeStore::new())
+        .manage(config)
+        .attach(reaper);
+
+    match acme_config {
+        Some(acme_config) => {
+            let renew_interval = acme_config.renew_interval;
+            rocket.attach(acme::provisioning_fairing(acme_config, renew_interval))
+        }
+        None => rocket,
+    }
 }
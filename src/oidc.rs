@@ -0,0 +1,223 @@
+use crate::config::{AssuranceLevel, OidcConfig};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rocket::tokio::sync::RwLock;
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error as StdError, fmt::Display, sync::Arc};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum Error {
+    Discovery(reqwest::Error),
+    TokenRequest(reqwest::Error),
+    Jwks(reqwest::Error),
+    UnknownKey(String),
+    Jwt(jsonwebtoken::errors::Error),
+    NonceMismatch,
+    UnknownState,
+    Url(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Discovery(e) => write!(f, "failed to fetch provider discovery document: {}", e),
+            Error::TokenRequest(e) => write!(f, "token exchange failed: {}", e),
+            Error::Jwks(e) => write!(f, "failed to fetch provider JWKS: {}", e),
+            Error::UnknownKey(kid) => write!(f, "no JWKS key matching kid {}", kid),
+            Error::Jwt(e) => write!(f, "ID token validation failed: {}", e),
+            Error::NonceMismatch => write!(f, "ID token nonce does not match the stored nonce"),
+            Error::UnknownState => write!(f, "unknown or expired OIDC state"),
+            Error::Url(e) => write!(f, "invalid provider URL: {}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Discovery(e) => Some(e),
+            Error::TokenRequest(e) => Some(e),
+            Error::Jwks(e) => Some(e),
+            Error::Jwt(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kid: String,
+    n: String,
+    e: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// What the confirm/browser flow was about to do before it was redirected
+/// off to the OIDC provider; restored in `/oidc/callback` once the user returns.
+#[derive(Debug, Clone)]
+pub struct PendingAuth {
+    pub attributes: Vec<String>,
+    pub continuation: String,
+    pub attr_url: Option<String>,
+    pub nonce: String,
+    pub assurance: AssuranceLevel,
+}
+
+/// Tracks in-flight OIDC authorization requests by `state`.
+#[derive(Clone)]
+pub struct OidcStateStore {
+    pending: Arc<RwLock<HashMap<String, PendingAuth>>>,
+}
+
+impl OidcStateStore {
+    pub fn new() -> Self {
+        OidcStateStore {
+            pending: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn start(&self, pending: PendingAuth) -> String {
+        let state = Uuid::new_v4().to_string();
+        self.pending.write().await.insert(state.clone(), pending);
+        state
+    }
+
+    pub async fn take(&self, state: &str) -> Option<PendingAuth> {
+        self.pending.write().await.remove(state)
+    }
+}
+
+impl Default for OidcStateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generates a fresh OIDC `nonce` value to embed in the authorization request
+/// and later check against the returned ID token's `nonce` claim.
+pub fn new_nonce() -> String {
+    Uuid::new_v4().to_string()
+}
+
+async fn fetch_discovery(client: &reqwest::Client, issuer_url: &str) -> Result<Discovery, Error> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer_url.trim_end_matches('/')
+    );
+    client
+        .get(url)
+        .send()
+        .await
+        .map_err(Error::Discovery)?
+        .json()
+        .await
+        .map_err(Error::Discovery)
+}
+
+/// Builds the authorization-endpoint URL the user is redirected to, carrying
+/// the `state`/`nonce` that tie the eventual callback back to this request.
+pub async fn authorization_url(
+    config: &OidcConfig,
+    client: &reqwest::Client,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+) -> Result<String, Error> {
+    let discovery = fetch_discovery(client, &config.issuer_url).await?;
+
+    let mut url =
+        reqwest::Url::parse(&discovery.authorization_endpoint).map_err(|e| Error::Url(e.to_string()))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &config.scopes.join(" "))
+        .append_pair("state", state)
+        .append_pair("nonce", nonce);
+
+    Ok(url.into())
+}
+
+/// Exchanges an authorization `code` for an ID token and validates its
+/// signature, `iss`, `aud`, `exp` and `nonce`, returning the verified claims.
+pub async fn exchange_and_validate(
+    config: &OidcConfig,
+    client: &reqwest::Client,
+    redirect_uri: &str,
+    code: &str,
+    expected_nonce: &str,
+) -> Result<serde_json::Value, Error> {
+    let discovery = fetch_discovery(client, &config.issuer_url).await?;
+
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", &config.client_id),
+        ("client_secret", &config.client_secret),
+    ];
+    let token_response: TokenResponse = client
+        .post(&discovery.token_endpoint)
+        .form(&params)
+        .send()
+        .await
+        .map_err(Error::TokenRequest)?
+        .json()
+        .await
+        .map_err(Error::TokenRequest)?;
+
+    let header = decode_header(&token_response.id_token).map_err(Error::Jwt)?;
+    let kid = header
+        .kid
+        .ok_or_else(|| Error::UnknownKey("<missing>".to_string()))?;
+
+    let jwks: Jwks = client
+        .get(&discovery.jwks_uri)
+        .send()
+        .await
+        .map_err(Error::Jwks)?
+        .json()
+        .await
+        .map_err(Error::Jwks)?;
+    let jwk = jwks
+        .keys
+        .iter()
+        .find(|k| k.kid == kid)
+        .ok_or_else(|| Error::UnknownKey(kid.clone()))?;
+
+    let decoding_key = DecodingKey::from_rsa_components(&jwk.n, &jwk.e).map_err(Error::Jwt)?;
+
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&config.issuer_url]);
+
+    let token_data = decode::<serde_json::Value>(&token_response.id_token, &decoding_key, &validation)
+        .map_err(Error::Jwt)?;
+
+    let nonce = token_data
+        .claims
+        .get("nonce")
+        .and_then(|v| v.as_str())
+        .ok_or(Error::NonceMismatch)?;
+    if nonce != expected_nonce {
+        return Err(Error::NonceMismatch);
+    }
+
+    Ok(token_data.claims)
+}
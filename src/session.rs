@@ -0,0 +1,162 @@
+use id_contact_proto::SessionActivity;
+use rocket::{
+    fairing::AdHoc,
+    tokio::{sync::RwLock, time},
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+pub type SessionId = String;
+
+/// Status of a single tracked session, mirroring the lifecycle the ID-Contact
+/// session protocol expects client applications to report on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    Active,
+    Extended,
+    Ended,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    pub created_at: Instant,
+    pub last_activity: Instant,
+    pub status: SessionStatus,
+}
+
+impl SessionState {
+    fn new() -> Self {
+        let now = Instant::now();
+        SessionState {
+            created_at: now,
+            last_activity: now,
+            status: SessionStatus::Active,
+        }
+    }
+
+    fn apply(&mut self, activity: SessionActivity) {
+        // Once a session has ended it is terminal: further activity reports
+        // (e.g. a late `Extend` racing the client's own teardown) must not
+        // revive it.
+        if self.status == SessionStatus::Ended {
+            return;
+        }
+        match activity {
+            SessionActivity::Extend => {
+                self.last_activity = Instant::now();
+                self.status = SessionStatus::Extended;
+            }
+            SessionActivity::Ended => {
+                self.status = SessionStatus::Ended;
+            }
+        }
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.last_activity.elapsed() > ttl
+    }
+}
+
+/// Rocket-managed session state, cheaply `Clone`able so the reaper task can
+/// hold its own handle independently of the request-bound `State` guard.
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Arc<RwLock<HashMap<SessionId, SessionState>>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        SessionStore {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Registers a new session and returns its freshly generated id.
+    pub async fn create(&self) -> SessionId {
+        let id = Uuid::new_v4().to_string();
+        self.sessions.write().await.insert(id.clone(), SessionState::new());
+        id
+    }
+
+    /// Applies a reported `SessionActivity` to the session with the given id.
+    /// Returns `None` if no such session is known (e.g. it expired or never existed).
+    pub async fn update(&self, id: &str, activity: SessionActivity) -> Option<()> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(id)?;
+        session.apply(activity);
+        Some(())
+    }
+
+    async fn reap_expired(&self, ttl: Duration) {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| !session.is_expired(ttl));
+        let reaped = before - sessions.len();
+        if reaped > 0 {
+            println!("Reaped {} expired session(s)", reaped);
+        }
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ended_status_is_terminal() {
+        let mut session = SessionState::new();
+        session.apply(SessionActivity::Ended);
+        session.apply(SessionActivity::Extend);
+        assert_eq!(session.status, SessionStatus::Ended);
+    }
+
+    #[test]
+    fn extend_refreshes_last_activity_and_status() {
+        let mut session = SessionState::new();
+        let created_last_activity = session.last_activity;
+        session.apply(SessionActivity::Extend);
+        assert_eq!(session.status, SessionStatus::Extended);
+        assert!(session.last_activity >= created_last_activity);
+    }
+
+    #[test]
+    fn session_expires_once_ttl_has_elapsed() {
+        let session = SessionState::new();
+        assert!(!session.is_expired(Duration::from_secs(300)));
+        assert!(session.is_expired(Duration::from_secs(0)));
+    }
+}
+
+/// Fairing that periodically sweeps the managed `SessionStore` for sessions
+/// whose last activity exceeds `ttl`, checking every `interval`.
+pub fn reaper_fairing(ttl: Duration, interval: Duration) -> AdHoc {
+    AdHoc::on_liftoff("Session reaper", move |rocket| {
+        Box::pin(async move {
+            let store = match rocket.state::<SessionStore>() {
+                Some(store) => store.clone(),
+                None => {
+                    println!("Session reaper not started: no SessionStore in managed state");
+                    return;
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                let mut ticker = time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    store.reap_expired(ttl).await;
+                }
+            });
+        })
+    })
+}